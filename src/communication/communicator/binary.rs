@@ -1,6 +1,11 @@
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Sender, Receiver, channel};
+use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::ops::Deref;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::mem;
 
 use serialization::Serializable;
 use networking::networking::MessageHeader;
@@ -10,6 +15,16 @@ use communication::{Communicator, Data, Message, Pullable};
 
 // TODO : wrap (usize, usize, usize) as a type?
 
+// Size of a freshly-allocated backing buffer, and the unit in which buffers
+// are recycled through a pool's free-list. Large enough that a batch of
+// `give`s typically lands in one buffer; small enough that an idle channel
+// isn't pinning down much memory.
+const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+// Starting point (and floor) for `SendEndpointInner`'s adaptive size hint,
+// used before any message has been committed.
+const RESERVE_HINT: usize = 1 << 10;
+
 // A communicator intended for binary channels (networking, pipes, shared memory)
 pub struct Binary {
     pub inner:      Process,    // inner Process (use for process-local channels)
@@ -19,16 +34,20 @@ pub struct Binary {
     pub allocated:  usize,                    // indicates how many channels have been allocated (locally).
 
     // for loading up state in the networking threads.
-    pub writers:    Vec<Sender<((usize, usize, usize), Sender<Vec<u8>>)>>,
-    pub readers:    Vec<Sender<((usize, usize, usize), (Sender<Vec<u8>>, Receiver<Vec<u8>>))>>,
-    pub senders:    Vec<Sender<(MessageHeader, Vec<u8>)>>
+    pub writers:    Vec<Sender<((usize, usize, usize), Sender<Bytes>)>>,
+    pub readers:    Vec<Sender<((usize, usize, usize), (Sender<Bytes>, Receiver<Bytes>))>>,
+    pub senders:    Vec<SendEndpoint>,
+
+    // backing allocations recycled between `senders` (writing) and `readers` (reading),
+    // so steady-state exchange settles into near-zero heap churn.
+    pub pool:       Arc<Mutex<Vec<Vec<u8>>>>,
 }
 
 impl Binary {
     pub fn inner<'a>(&'a mut self) -> &'a mut Process { &mut self.inner }
 }
 
-// A Communicator backed by Sender<Vec<u8>>/Receiver<Vec<u8>> pairs (e.g. networking, shared memory, files, pipes)
+// A Communicator backed by Sender<Bytes>/Receiver<Bytes> pairs (e.g. networking, shared memory, files, pipes)
 impl Communicator for Binary {
     fn index(&self) -> usize { self.index }
     fn peers(&self) -> usize { self.peers }
@@ -82,18 +101,173 @@ impl Communicator for Binary {
     }
 }
 
+// An immutable, reference-counted slice of a shared backing buffer. Cloning
+// only bumps a refcount; when the last clone drops, the backing `Vec<u8>`
+// returns to the free-list it came from (if any).
+pub struct Bytes {
+    buffer: Arc<Vec<u8>>,
+    offset: usize,
+    length: usize,
+    pool:   Option<Arc<Mutex<Vec<Vec<u8>>>>>,
+}
+
+impl Bytes {
+    fn new(buffer: Arc<Vec<u8>>, offset: usize, length: usize, pool: Arc<Mutex<Vec<Vec<u8>>>>) -> Bytes {
+        Bytes { buffer: buffer, offset: offset, length: length, pool: Some(pool) }
+    }
+    pub fn len(&self) -> usize { self.length }
+    pub fn is_empty(&self) -> bool { self.length == 0 }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+    #[inline] fn deref(&self) -> &[u8] { &self.buffer[self.offset..(self.offset + self.length)] }
+}
+
+impl Clone for Bytes {
+    fn clone(&self) -> Bytes {
+        Bytes { buffer: self.buffer.clone(), offset: self.offset, length: self.length, pool: self.pool.clone() }
+    }
+}
+
+impl Drop for Bytes {
+    fn drop(&mut self) {
+        // Only the handle that drops the last reference to `buffer` returns it
+        // to the free-list; everyone else just drops their `Arc` as usual.
+        if let Some(pool) = self.pool.take() {
+            let buffer = mem::replace(&mut self.buffer, Arc::new(Vec::new()));
+            if let Ok(mut buffer) = Arc::try_unwrap(buffer) {
+                // Don't recycle an oversized buffer (e.g. from one unusually large
+                // message): that would permanently inflate the pool's steady-state
+                // footprint. Only buffers near our default size come back.
+                if buffer.capacity() <= DEFAULT_BUFFER_SIZE {
+                    buffer.clear();
+                    pool.lock().unwrap().push(buffer);
+                }
+            }
+        }
+    }
+}
+
+// A mutex-guarded queue of outbound (header, payload) pairs, shared between
+// the SendEndpoint for a given peer (cloned into every timely channel to that
+// peer) and the one networking thread writing to the socket; draining it in one pass turns
+// many small gives into relatively few system writes.
+#[derive(Clone)]
+pub struct MergeQueue {
+    queue: Arc<Mutex<VecDeque<(MessageHeader, Bytes)>>>,
+}
+
+impl MergeQueue {
+    pub fn new() -> MergeQueue {
+        MergeQueue { queue: Arc::new(Mutex::new(VecDeque::new())) }
+    }
+    fn push(&self, header: MessageHeader, bytes: Bytes) {
+        self.queue.lock().unwrap().push_back((header, bytes));
+    }
+    // Drains everything queued so far into `target`, for the networking
+    // thread to write out in one pass.
+    pub fn drain_into(&self, target: &mut Vec<(MessageHeader, Bytes)>) {
+        let mut queue = self.queue.lock().unwrap();
+        target.extend(queue.drain(..));
+    }
+}
+
+impl Default for MergeQueue {
+    fn default() -> MergeQueue { MergeQueue::new() }
+}
+
+// The mutable state behind a `SendEndpoint`: the buffer currently being
+// filled, and the (header, offset, length) of each message written into it
+// since the last flush.
+struct SendEndpointInner {
+    buffer: Vec<u8>,
+    staged: Vec<(MessageHeader, usize, usize)>,
+    queue:  MergeQueue,
+    pool:   Arc<Mutex<Vec<Vec<u8>>>>,
+    hint:   usize,   // size of the last committed message; our guess for the next `reserve`
+}
+
+impl SendEndpointInner {
+    fn new(queue: MergeQueue, pool: Arc<Mutex<Vec<Vec<u8>>>>) -> SendEndpointInner {
+        SendEndpointInner {
+            buffer: Vec::new(),
+            staged: Vec::new(),
+            queue:  queue,
+            pool:   pool,
+            hint:   RESERVE_HINT,
+        }
+    }
+
+    // Ensures spare capacity at the write head for a message around `self.hint`
+    // bytes, flushing (and recycling from `pool`) first if the current buffer
+    // doesn't have it -- so a run of large messages rotates to fresh buffers
+    // promptly instead of growing one buffer past `DEFAULT_BUFFER_SIZE`.
+    fn reserve(&mut self) -> &mut Vec<u8> {
+        let len = self.hint;
+        if self.buffer.capacity() - self.buffer.len() < len {
+            self.flush();
+            let fresh = self.pool.lock().unwrap().pop();
+            self.buffer = fresh.unwrap_or_else(|| Vec::with_capacity(DEFAULT_BUFFER_SIZE));
+            if self.buffer.capacity() < len { self.buffer.reserve(len); }
+        }
+        &mut self.buffer
+    }
+
+    // Records that `[start, buffer.len())` holds a just-written message bound
+    // for `header`, and updates `hint` from its actual size.
+    fn commit(&mut self, header: MessageHeader, start: usize) {
+        let end = self.buffer.len();
+        if end > start {
+            self.hint = ::std::cmp::max(RESERVE_HINT, end - start);
+            self.staged.push((header, start, end - start));
+        }
+    }
+
+    // Slices every staged message out of the filled buffer into its own
+    // `Bytes` (all sharing one `Arc`, and so one eventual free-list return),
+    // and pushes them to the `MergeQueue`.
+    fn flush(&mut self) {
+        if !self.staged.is_empty() {
+            let filled = mem::replace(&mut self.buffer, Vec::new());
+            let arc = Arc::new(filled);
+            for (header, offset, length) in self.staged.drain(..) {
+                self.queue.push(header, Bytes::new(arc.clone(), offset, length, self.pool.clone()));
+            }
+        }
+    }
+}
+
+// A shared, recyclable backing store for outbound serialized messages.
+// `Observer::give` encodes into it via reserve/commit instead of allocating
+// a fresh Vec per call; reserve/commit/flush live on the inner type above.
+#[derive(Clone)]
+pub struct SendEndpoint {
+    inner: Rc<RefCell<SendEndpointInner>>,
+}
+
+impl SendEndpoint {
+    pub fn new(queue: MergeQueue, pool: Arc<Mutex<Vec<Vec<u8>>>>) -> SendEndpoint {
+        SendEndpoint { inner: Rc::new(RefCell::new(SendEndpointInner::new(queue, pool))) }
+    }
+
+    // Flushes any messages staged since the last flush; called on `shut` so
+    // a quiet channel doesn't sit on unsent data waiting for its buffer to fill.
+    fn flush(&self) { self.inner.borrow_mut().flush(); }
+}
+
 struct Observer<T, D> {
     header:     MessageHeader,
-    sender:     Sender<(MessageHeader, Vec<u8>)>,   // targets for each remote destination
+    endpoint:   SendEndpoint,   // shared, recycled backing buffer for this target
     phantom:    PhantomData<D>,
     time: Option<T>,
 }
 
 impl<T, D> Observer<T, D> {
-    pub fn new(header: MessageHeader, sender: Sender<(MessageHeader, Vec<u8>)>) -> Observer<T, D> {
+    pub fn new(header: MessageHeader, endpoint: SendEndpoint) -> Observer<T, D> {
         Observer {
             header:     header,
-            sender:     sender,
+            endpoint:   endpoint,
             phantom:    PhantomData,
             time: None,
         }
@@ -111,27 +285,29 @@ impl<T:Data+Serializable, D:Data+Serializable> ::communication::observer::Observ
     #[inline] fn shut(&mut self,_time: &Self::Time) {
         assert!(self.time.is_some());
         self.time = None;
+        self.endpoint.flush();
     }
     #[inline] fn give(&mut self, data: &mut Message<Self::Data>) {
         assert!(self.time.is_some());
         if data.len() > 0 {
             if let Some(time) = self.time.clone() {
-                // TODO : anything better to do here than allocate (bytes)?
-                // TODO : perhaps team up with the Pushable to recycle (bytes) ...
-                // ALLOC : We create some new byte buffers here, because we have to.
-                // ALLOC : We would love to borrow these from somewhere nearby, if possible.
-                let mut bytes = Vec::new();
-                Serializable::encode(&time, &mut bytes);
-                let vec: &Vec<D> = data.deref();
-                Serializable::encode(vec, &mut bytes);
+                let mut header = self.header;
+
+                let mut inner = self.endpoint.inner.borrow_mut();
+                let start = {
+                    let buffer = inner.reserve();
+                    let start = buffer.len();
+                    Serializable::encode(&time, buffer);
+                    let vec: &Vec<D> = data.deref();
+                    Serializable::encode(vec, buffer);
+                    start
+                };
 
                 // NOTE : We do not .clear() data, because that could forcibly allocate.
                 // NOTE : Instead, upstream folks are expected to clear allocations before re-using.
 
-                let mut header = self.header;
-                header.length = bytes.len();
-
-                self.sender.send((header, bytes)).ok();
+                header.length = inner.buffer.len() - start;
+                inner.commit(header, start);
             }
         }
     }
@@ -140,10 +316,10 @@ impl<T:Data+Serializable, D:Data+Serializable> ::communication::observer::Observ
 struct BinaryPullable<T, D> {
     inner: Box<Pullable<T, D>>,       // inner pullable (e.g. intra-process typed queue)
     current: Option<(T, Message<D>)>,
-    receiver: Receiver<Vec<u8>>,      // source of serialized buffers
+    receiver: Receiver<Bytes>,        // source of serialized buffers
 }
 impl<T:Data+Serializable, D: Data+Serializable> BinaryPullable<T, D> {
-    fn new(inner: Box<Pullable<T, D>>, receiver: Receiver<Vec<u8>>) -> BinaryPullable<T, D> {
+    fn new(inner: Box<Pullable<T, D>>, receiver: Receiver<Bytes>) -> BinaryPullable<T, D> {
         BinaryPullable {
             inner: inner,
             current: None,
@@ -158,7 +334,15 @@ impl<T:Data+Serializable, D: Data+Serializable> Pullable<T, D> for BinaryPullabl
         if let Some(pair) = self.inner.pull() { Some(pair) }
         else {
             // TODO : Do something better than drop self.current
-            self.current = self.receiver.try_recv().ok().map(|mut bytes| {
+            self.current = self.receiver.try_recv().ok().map(|bytes| {
+                // `decode` mutates its input in place to patch up internal pointers,
+                // which `Bytes` can't offer safely: several `Bytes` may alias the same
+                // backing `Vec<u8>` from one flushed `SendEndpoint` buffer. So we take
+                // a private, uniquely-owned copy to decode into here -- this gives up
+                // the zero-copy win on the read side, but the `Bytes` itself is dropped
+                // right after, and once the last handle on its buffer is gone the
+                // allocation returns to the pool for a `SendEndpoint` to reuse.
+                let mut bytes = bytes.to_vec();
                 let x_len = bytes.len();
                 let (time, offset) = {
                     let (t,r) = <T as Serializable>::decode(&mut bytes).unwrap();
@@ -175,4 +359,4 @@ impl<T:Data+Serializable, D: Data+Serializable> Pullable<T, D> for BinaryPullabl
             self.current.as_mut().map(|&mut (ref time, ref mut data)| (time, data))
         }
     }
-}
\ No newline at end of file
+}